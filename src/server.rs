@@ -2,78 +2,717 @@ use crate::message::EchoMessage;
 use log::{error, info, warn};
 use prost::Message;
 use std::{
+    collections::BTreeMap,
+    env, fmt,
     io::{self, ErrorKind, Read, Write},
-    net::{TcpListener, TcpStream},
+    net::{SocketAddr, TcpListener, TcpStream},
+    os::unix::{
+        io::{FromRawFd, RawFd},
+        net::{SocketAddr as UnixSocketAddr, UnixListener, UnixStream},
+    },
+    path::Path,
+    process,
     sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
     },
-    thread,
-    time::Duration,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
+/// Default number of worker threads used when a server is created with [`Server::new`].
+const DEFAULT_WORKERS: usize = 4;
+
+/// Default cap on simultaneously active connections, used when a server is
+/// created without an explicit [`Server::set_max_connections`] call.
+const DEFAULT_MAX_CONNECTIONS: usize = 1024;
+
+/// How far below `max_connections` the active count must drop before the
+/// acceptor resumes accepting, to avoid flapping right at the cap.
+const LOW_WATER_MARGIN: usize = 10;
+
+/// Margin added on top of `read_timeout` when computing how long
+/// `Server::stop` waits for the acceptor and worker threads to join before
+/// giving up and returning anyway. A worker blocked in a read can't notice
+/// shutdown until that read unblocks (after `read_timeout`), so the join
+/// timeout has to be at least that long, plus some slack for thread
+/// scheduling.
+const SHUTDOWN_JOIN_MARGIN: Duration = Duration::from_secs(5);
+
+/// Default cap on a single frame's declared payload size, used when a server
+/// is created without an explicit [`Server::set_max_frame_size`] call.
+const DEFAULT_MAX_FRAME_SIZE: u32 = 1024 * 1024;
+
+/// Default per-read timeout, used when a server is created without an
+/// explicit [`Server::set_read_timeout`] call.
+const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default idle limit before a silent connection is reaped, used when a
+/// server is created without an explicit [`Server::set_idle_timeout`] call.
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Number of bytes used for the big-endian length prefix on each frame.
+const LENGTH_PREFIX_SIZE: usize = 4;
+
+/// Upper bound on a single write to a registered broadcast peer, so a stalled
+/// reader can't block delivery to the rest of the registry indefinitely.
+const DEFAULT_BROADCAST_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many consecutive stalled (`WouldBlock`/`TimedOut`) broadcast writes a
+/// peer tolerates before it's treated as gone and pruned from the registry.
+/// Without this, a connected-but-unresponsive peer would fail the same way
+/// forever, costing every future broadcast a full write-timeout wait.
+const MAX_CONSECUTIVE_BROADCAST_STALLS: u32 = 3;
+
+/// Outcome of attempting to read one frame off a client connection.
+enum Frame {
+    Message(Vec<u8>),
+    Disconnected,
+    /// The read timed out with no data available; the caller decides whether
+    /// that means the connection has simply been idle or should be reaped.
+    TimedOut,
+}
+
+/// Either kind of socket `Server` can listen on.
+enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    fn try_clone(&self) -> io::Result<Listener> {
+        match self {
+            Listener::Tcp(l) => Ok(Listener::Tcp(l.try_clone()?)),
+            Listener::Unix(l) => Ok(Listener::Unix(l.try_clone()?)),
+        }
+    }
+
+    /// Human-readable address, used for the "server is running on" log line.
+    fn describe(&self) -> io::Result<String> {
+        match self {
+            Listener::Tcp(l) => Ok(l.local_addr()?.to_string()),
+            Listener::Unix(l) => Ok(match l.local_addr()?.as_pathname() {
+                Some(path) => path.display().to_string(),
+                None => "<unnamed unix socket>".to_string(),
+            }),
+        }
+    }
+
+    /// The bound TCP address, if this listener is backed by a `TcpListener`.
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Listener::Tcp(l) => l.local_addr(),
+            Listener::Unix(_) => Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "local_addr is only available for TCP listeners",
+            )),
+        }
+    }
+
+    fn accept(&self) -> io::Result<(Stream, ClientAddr)> {
+        match self {
+            Listener::Tcp(l) => {
+                let (stream, addr) = l.accept()?;
+                Ok((Stream::Tcp(stream), ClientAddr::Tcp(addr)))
+            }
+            Listener::Unix(l) => {
+                let (stream, addr) = l.accept()?;
+                Ok((Stream::Unix(stream), ClientAddr::Unix(addr)))
+            }
+        }
+    }
+
+    /// Puts the listener in non-blocking mode so the accept loop can poll
+    /// `is_running` instead of sitting blocked inside `accept()` forever.
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Listener::Tcp(l) => l.set_nonblocking(nonblocking),
+            Listener::Unix(l) => l.set_nonblocking(nonblocking),
+        }
+    }
+}
+
+/// Either kind of connected socket a [`Client`] can wrap.
+enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Stream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(s) => s.set_read_timeout(timeout),
+            Stream::Unix(s) => s.set_read_timeout(timeout),
+        }
+    }
+
+    /// Bounds how long a single `write` can block, so one stalled peer in a
+    /// broadcast can't wedge delivery to the rest of the registry forever.
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(s) => s.set_write_timeout(timeout),
+            Stream::Unix(s) => s.set_write_timeout(timeout),
+        }
+    }
+
+    /// Clones the underlying socket handle so it can be registered for
+    /// broadcast writes while the original is read from elsewhere.
+    fn try_clone(&self) -> io::Result<Stream> {
+        match self {
+            Stream::Tcp(s) => Ok(Stream::Tcp(s.try_clone()?)),
+            Stream::Unix(s) => Ok(Stream::Unix(s.try_clone()?)),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(s) => s.read(buf),
+            Stream::Unix(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(s) => s.write(buf),
+            Stream::Unix(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(s) => s.flush(),
+            Stream::Unix(s) => s.flush(),
+        }
+    }
+}
+
+/// Peer address of an accepted connection, logged alongside client activity.
+#[derive(Clone)]
+enum ClientAddr {
+    Tcp(SocketAddr),
+    Unix(UnixSocketAddr),
+}
+
+impl fmt::Display for ClientAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientAddr::Tcp(addr) => write!(f, "{}", addr),
+            ClientAddr::Unix(addr) => match addr.as_pathname() {
+                Some(path) => write!(f, "{}", path.display()),
+                None => write!(f, "<unnamed unix socket>"),
+            },
+        }
+    }
+}
+
 struct Client {
-    stream: TcpStream,
+    stream: Stream,
+    max_frame_size: u32,
+    idle_timeout: Duration,
+    idle_since: Instant,
+    /// When set, a received message is broadcast to every registered peer
+    /// instead of being echoed back only to the sender.
+    broadcast: Option<ClientRegistry>,
 }
 
 impl Client {
-    pub fn new(stream: TcpStream) -> Self {
-        Client { stream }
+    pub fn new(
+        stream: Stream,
+        max_frame_size: u32,
+        read_timeout: Duration,
+        idle_timeout: Duration,
+        broadcast: Option<ClientRegistry>,
+    ) -> io::Result<Self> {
+        stream.set_read_timeout(Some(read_timeout))?;
+        Ok(Client {
+            stream,
+            max_frame_size,
+            idle_timeout,
+            idle_since: Instant::now(),
+            broadcast,
+        })
     }
 
-    pub fn handle(&mut self) -> io::Result<()> {
-        let mut buffer = [0; 512];
-        // Read data from the client
-        let bytes_read = self.stream.read(&mut buffer)?;
-        if bytes_read == 0 {
-            info!("Client disconnected.");
-            return Ok(());
+    /// Reads one length-prefixed frame, returning `Frame::TimedOut` if the read
+    /// timeout elapses before a full header is available.
+    fn read_frame(&mut self) -> io::Result<Frame> {
+        let mut len_buf = [0; LENGTH_PREFIX_SIZE];
+        match self.stream.read_exact(&mut len_buf) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(Frame::Disconnected),
+            Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+                return Ok(Frame::TimedOut)
+            }
+            Err(e) => return Err(e),
         }
 
-        if let Ok(message) = EchoMessage::decode(&buffer[..bytes_read]) {
+        let len = u32::from_be_bytes(len_buf);
+        if len > self.max_frame_size {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "frame of {} bytes exceeds max_frame_size of {} bytes",
+                    len, self.max_frame_size
+                ),
+            ));
+        }
+
+        let mut payload = vec![0; len as usize];
+        self.stream.read_exact(&mut payload)?;
+        Ok(Frame::Message(payload))
+    }
+
+    /// Handles at most one frame. Returns `Ok(false)` once the client has
+    /// disconnected, so the worker loop in `Server::run` knows to stop
+    /// calling `handle` and return the worker to the pool instead of
+    /// spinning on a closed stream. A handled frame, or a read timeout while
+    /// the client is still within `idle_timeout`, returns `Ok(true)`; an
+    /// oversized frame, an exceeded `idle_timeout` or an I/O error returns `Err`.
+    pub fn handle(&mut self) -> io::Result<bool> {
+        let payload = match self.read_frame()? {
+            Frame::Message(payload) => payload,
+            Frame::Disconnected => {
+                info!("Client disconnected.");
+                return Ok(false);
+            }
+            Frame::TimedOut => {
+                // No data yet: give the worker loop a chance to re-check
+                // `is_running`, and only give up once the client has been
+                // silent for longer than `idle_timeout`.
+                if self.idle_since.elapsed() >= self.idle_timeout {
+                    return Err(io::Error::new(
+                        ErrorKind::TimedOut,
+                        format!("client idle for longer than {:?}", self.idle_timeout),
+                    ));
+                }
+                return Ok(true);
+            }
+        };
+
+        self.idle_since = Instant::now();
+        if let Ok(message) = EchoMessage::decode(payload.as_slice()) {
             info!("Received: {}", message.content);
-            // Echo back the message
-            let payload = message.encode_to_vec();
-            self.stream.write_all(&payload)?;
-            self.stream.flush()?;
+            let reply = message.encode_to_vec();
+            match &self.broadcast {
+                // Broadcast mode: fan the message out to every registered peer
+                Some(registry) => broadcast_frame(registry, &reply),
+                // Default mode: echo back only to the sender
+                None => write_frame(&mut self.stream, &reply)?,
+            }
         } else {
             error!("Failed to decode message");
         }
 
-        Ok(())
+        Ok(true)
+    }
+}
+
+/// A connection handed off from the acceptor thread to a worker.
+type Job = (Stream, ClientAddr);
+
+/// A registered broadcast peer: its write handle, plus a count of how many
+/// broadcasts in a row have stalled (rather than failed outright) writing to
+/// it, used to prune peers that are connected but no longer reading.
+struct BroadcastPeer {
+    stream: Mutex<Stream>,
+    consecutive_stalls: AtomicU32,
+}
+
+/// Registry of connected clients' write handles, keyed by a monotonically
+/// increasing id, used to fan a message out to every connected peer. Each
+/// peer's stream has its own lock so a blocking write to one peer doesn't
+/// hold up registration/deregistration of unrelated clients.
+type ClientRegistry = Arc<Mutex<BTreeMap<u64, Arc<BroadcastPeer>>>>;
+
+/// Length-prefixes `payload` and writes it to every stream in `registry`,
+/// pruning any peer whose write fails because it has gone away, or whose
+/// writes have stalled (timed out rather than errored outright) for
+/// `MAX_CONSECUTIVE_BROADCAST_STALLS` broadcasts in a row.
+///
+/// The registry lock is only held long enough to snapshot the current peers
+/// and, afterwards, to remove any that went dead; the writes themselves,
+/// which are bounded by `DEFAULT_BROADCAST_WRITE_TIMEOUT`, happen with the
+/// registry unlocked so a stalled peer can't block other workers from
+/// registering or deregistering clients.
+fn broadcast_frame(registry: &ClientRegistry, payload: &[u8]) {
+    let peers: Vec<(u64, Arc<BroadcastPeer>)> = registry
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(&id, peer)| (id, Arc::clone(peer)))
+        .collect();
+
+    let mut dead = Vec::new();
+    for (id, peer) in peers {
+        match write_frame(&mut peer.stream.lock().unwrap(), payload) {
+            Ok(()) => peer.consecutive_stalls.store(0, Ordering::SeqCst),
+            Err(e) if matches!(e.kind(), ErrorKind::ConnectionReset | ErrorKind::BrokenPipe) => {
+                info!("Pruning disconnected broadcast peer {}", id);
+                dead.push(id);
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                let stalls = peer.consecutive_stalls.fetch_add(1, Ordering::SeqCst) + 1;
+                if stalls >= MAX_CONSECUTIVE_BROADCAST_STALLS {
+                    warn!(
+                        "Pruning broadcast peer {} after {} consecutive stalled writes",
+                        id, stalls
+                    );
+                    dead.push(id);
+                } else {
+                    warn!(
+                        "Write to broadcast peer {} stalled ({}/{} consecutive)",
+                        id, stalls, MAX_CONSECUTIVE_BROADCAST_STALLS
+                    );
+                }
+            }
+            Err(e) => error!("Failed to broadcast to client {}: {}", id, e),
+        }
+    }
+    if !dead.is_empty() {
+        let mut registry = registry.lock().unwrap();
+        for id in dead {
+            registry.remove(&id);
+        }
+    }
+}
+
+/// Writes one length-prefixed frame to `stream`.
+fn write_frame(stream: &mut Stream, payload: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(payload.len())
+        .map_err(|_| io::Error::new(ErrorKind::InvalidData, "payload too large to frame"))?;
+    stream.write_all(&len.to_be_bytes())?;
+    stream.write_all(payload)?;
+    stream.flush()
+}
+
+/// Handles for the acceptor and worker threads spawned by [`Server::run`],
+/// kept on `Server` so `stop` can join them on shutdown.
+struct RunGuard {
+    acceptor: JoinHandle<()>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl RunGuard {
+    /// Blocks until the acceptor thread and every worker thread have exited.
+    fn join(self) {
+        if let Err(e) = self.acceptor.join() {
+            error!("Acceptor thread panicked: {:?}", e);
+        }
+        for worker in self.workers {
+            if let Err(e) = worker.join() {
+                error!("Worker thread panicked: {:?}", e);
+            }
+        }
     }
 }
 
 pub struct Server {
-    listener: TcpListener,
+    listener: Listener,
     is_running: Arc<AtomicBool>,
+    workers: usize,
+    max_frame_size: u32,
+    read_timeout: Duration,
+    idle_timeout: Duration,
+    broadcast_mode: bool,
+    registry: ClientRegistry,
+    next_client_id: Arc<AtomicU64>,
+    max_connections: usize,
+    active_connections: Arc<AtomicUsize>,
+    run_guard: Mutex<Option<RunGuard>>,
 }
 
 impl Server {
-    /// Creates a new server instance
+    /// Creates a new server instance with a default-sized worker pool
     pub fn new(addr: &str) -> io::Result<Self> {
-        let listener = TcpListener::bind(addr)?;
-        let is_running = Arc::new(AtomicBool::new(false));
-        Ok(Server {
+        Self::with_workers(addr, DEFAULT_WORKERS)
+    }
+
+    /// Creates a new server instance backed by a fixed pool of `workers` threads,
+    /// instead of spawning a new OS thread per connection
+    pub fn with_workers(addr: &str, workers: usize) -> io::Result<Self> {
+        let listener = Listener::Tcp(TcpListener::bind(addr)?);
+        Ok(Self::from_listener(listener, workers))
+    }
+
+    /// Creates a new server listening on a Unix domain socket at `path`
+    /// instead of a TCP address.
+    pub fn new_unix<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::new_unix_with_workers(path, DEFAULT_WORKERS)
+    }
+
+    /// Same as [`Server::new_unix`], but with an explicit worker pool size.
+    pub fn new_unix_with_workers<P: AsRef<Path>>(path: P, workers: usize) -> io::Result<Self> {
+        let listener = Listener::Unix(UnixListener::bind(path)?);
+        Ok(Self::from_listener(listener, workers))
+    }
+
+    /// Creates a server by adopting a pre-opened socket passed in by
+    /// systemd/inetd-style socket activation, instead of binding a new one.
+    ///
+    /// Inspects the `LISTEN_PID`/`LISTEN_FDS` environment variables; if
+    /// `LISTEN_PID` matches this process and at least one file descriptor was
+    /// passed, the first activated descriptor (fd 3, per the `sd_listen_fds`
+    /// convention) is adopted as a `TcpListener`.
+    pub fn from_activation() -> io::Result<Self> {
+        Self::from_activation_with_workers(DEFAULT_WORKERS)
+    }
+
+    /// Same as [`Server::from_activation`], but with an explicit worker pool size.
+    pub fn from_activation_with_workers(workers: usize) -> io::Result<Self> {
+        const FIRST_ACTIVATED_FD: RawFd = 3;
+
+        let listen_pid: u32 = env::var("LISTEN_PID")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        let listen_fds: usize = env::var("LISTEN_FDS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+
+        if listen_pid != process::id() || listen_fds < 1 {
+            return Err(io::Error::new(
+                ErrorKind::NotFound,
+                "no systemd socket activation descriptors available \
+                 (LISTEN_PID/LISTEN_FDS not set for this process)",
+            ));
+        }
+
+        // Safety: LISTEN_PID matching our pid means systemd passed us this fd
+        // already open and ready to accept, per the sd_listen_fds contract.
+        let listener = unsafe { TcpListener::from_raw_fd(FIRST_ACTIVATED_FD) };
+        info!(
+            "Adopted activated socket fd {} from systemd (pid {}).",
+            FIRST_ACTIVATED_FD, listen_pid
+        );
+        Ok(Self::from_listener(Listener::Tcp(listener), workers))
+    }
+
+    fn from_listener(listener: Listener, workers: usize) -> Self {
+        Server {
             listener,
-            is_running,
-        })
+            is_running: Arc::new(AtomicBool::new(false)),
+            workers,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            read_timeout: DEFAULT_READ_TIMEOUT,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            broadcast_mode: false,
+            registry: Arc::new(Mutex::new(BTreeMap::new())),
+            next_client_id: Arc::new(AtomicU64::new(0)),
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            active_connections: Arc::new(AtomicUsize::new(0)),
+            run_guard: Mutex::new(None),
+        }
+    }
+
+    /// The number of worker threads this server was configured with
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+
+    /// The address this server is bound to, if it is backed by a TCP listener.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Sets the largest frame payload (in bytes) a client connection will accept.
+    /// Frames declaring a larger length are rejected with `ErrorKind::InvalidData`.
+    pub fn set_max_frame_size(&mut self, max_frame_size: u32) {
+        self.max_frame_size = max_frame_size;
+    }
+
+    /// Sets how long a client connection's `read` can block before the worker
+    /// loop wakes up to re-check `is_running` and the idle timeout.
+    pub fn set_read_timeout(&mut self, read_timeout: Duration) {
+        self.read_timeout = read_timeout;
+    }
+
+    /// Sets how long a client connection may go without sending any data
+    /// before it is reaped.
+    pub fn set_idle_timeout(&mut self, idle_timeout: Duration) {
+        self.idle_timeout = idle_timeout;
+    }
+
+    /// Enables broadcast mode: a message received from one client is echoed
+    /// to every currently connected client instead of only back to the sender.
+    pub fn set_broadcast_mode(&mut self, enabled: bool) {
+        self.broadcast_mode = enabled;
     }
 
-    /// Runs the server, listening for incoming connections and handling them
+    /// Sends `message` to every currently connected client, pruning any peer
+    /// whose write fails because it has disconnected.
+    pub fn broadcast(&self, message: &EchoMessage) -> io::Result<()> {
+        broadcast_frame(&self.registry, &message.encode_to_vec());
+        Ok(())
+    }
+
+    /// Caps the number of simultaneously active connections. Once reached,
+    /// the acceptor stops accepting new connections until the count drops
+    /// back to `max_connections - `[`LOW_WATER_MARGIN`] (floored at zero) or below.
+    pub fn set_max_connections(&mut self, max_connections: usize) {
+        self.max_connections = max_connections;
+    }
+
+    /// Runs the server, listening for incoming connections and handling them.
+    ///
+    /// Spawns the acceptor and worker threads and returns immediately; their
+    /// handles are kept internally (in `run_guard`) rather than returned, so
+    /// callers don't need to hold onto anything to shut the server down
+    /// later. Use `stop` to signal shutdown and wait for those threads to
+    /// join, or `join` to just wait for them to exit on their own.
     pub fn run(&self) -> io::Result<()> {
         // Set the server as running
         self.is_running.store(true, Ordering::SeqCst); // Set the server as running
-        info!("Server is running on {}", self.listener.local_addr()?);
+        info!("Server is running on {}", self.listener.describe()?);
 
         // Wrap the listener in an Arc<Mutex> for safe multi-threaded access
-        let listener = Arc::new(Mutex::new(self.listener.try_clone()?));
+        let listener = self.listener.try_clone()?;
+        // Non-blocking so the accept loop below can poll `is_running` instead
+        // of sitting blocked inside `accept()` until the next connection
+        listener.set_nonblocking(true)?;
+        let listener = Arc::new(Mutex::new(listener));
         // Clone the `is_running` flag for use in the listener thread
         let is_running = Arc::clone(&self.is_running);
 
+        // Shared channel feeding accepted connections to the fixed worker pool
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let mut workers = Vec::with_capacity(self.workers);
+        for id in 0..self.workers {
+            let receiver = Arc::clone(&receiver);
+            let is_running = Arc::clone(&is_running);
+            let max_frame_size = self.max_frame_size;
+            let read_timeout = self.read_timeout;
+            let idle_timeout = self.idle_timeout;
+            let broadcast_mode = self.broadcast_mode;
+            let registry = Arc::clone(&self.registry);
+            let next_client_id = Arc::clone(&self.next_client_id);
+            let active_connections = Arc::clone(&self.active_connections);
+            workers.push(thread::spawn(move || {
+                loop {
+                    // Hold the lock only long enough to pull the next job off the channel
+                    let job = receiver.lock().unwrap().recv();
+                    let (stream, addr) = match job {
+                        Ok(job) => job,
+                        Err(_) => {
+                            // Sender was dropped: no more work will ever arrive
+                            info!("Worker {} shutting down.", id);
+                            break;
+                        }
+                    };
+                    info!("Worker {} handling client: {}", id, addr);
+                    // Counted as active already, at accept time, so that connections
+                    // sitting in the channel waiting for a free worker also count
+                    // toward `max_connections`.
+
+                    // In broadcast mode, register a cloned write handle under its
+                    // own id before handing the original stream to the client
+                    let client_id = next_client_id.fetch_add(1, Ordering::SeqCst);
+                    let client_registry = if broadcast_mode {
+                        match stream.try_clone().and_then(|clone| {
+                            clone.set_write_timeout(Some(DEFAULT_BROADCAST_WRITE_TIMEOUT))?;
+                            Ok(clone)
+                        }) {
+                            Ok(clone) => {
+                                let peer = Arc::new(BroadcastPeer {
+                                    stream: Mutex::new(clone),
+                                    consecutive_stalls: AtomicU32::new(0),
+                                });
+                                registry.lock().unwrap().insert(client_id, peer);
+                                Some(Arc::clone(&registry))
+                            }
+                            Err(e) => {
+                                error!("Failed to register broadcast peer {}: {}", addr, e);
+                                None
+                            }
+                        }
+                    } else {
+                        None
+                    };
+
+                    let mut client = match Client::new(
+                        stream,
+                        max_frame_size,
+                        read_timeout,
+                        idle_timeout,
+                        client_registry,
+                    ) {
+                        Ok(client) => client,
+                        Err(e) => {
+                            error!("Failed to configure client {}: {}", addr, e);
+                            if broadcast_mode {
+                                registry.lock().unwrap().remove(&client_id);
+                            }
+                            active_connections.fetch_sub(1, Ordering::SeqCst);
+                            continue;
+                        }
+                    };
+
+                    // Handle client communication while the server is running
+                    while is_running.load(Ordering::SeqCst) {
+                        match client.handle() {
+                            Ok(true) => {}
+                            // Client disconnected cleanly: stop calling `handle` on this
+                            // stream so the worker returns to the pool instead of
+                            // spinning on a socket that will never yield more data.
+                            Ok(false) => break,
+                            Err(e) => {
+                                if e.kind() == ErrorKind::ConnectionReset {
+                                    info!("Client disconnected unexpectedly.");
+                                } else if e.kind() == ErrorKind::TimedOut {
+                                    info!("Reaping idle client {}: {}", addr, e);
+                                } else {
+                                    error!("Error handling client: {}", e);
+                                }
+                                break;
+                            }
+                        }
+                    }
+
+                    if broadcast_mode {
+                        registry.lock().unwrap().remove(&client_id);
+                    }
+                    active_connections.fetch_sub(1, Ordering::SeqCst);
+                }
+            }));
+        }
+
+        let max_connections = self.max_connections;
+        let active_connections = Arc::clone(&self.active_connections);
+        let low_water_mark = max_connections.saturating_sub(LOW_WATER_MARGIN);
+
         // Spawn the listener thread to handle incoming connections
-        thread::spawn(move || {
+        let acceptor = thread::spawn(move || {
+            let mut paused = false;
             while is_running.load(Ordering::SeqCst) {
+                let active = active_connections.load(Ordering::SeqCst);
+                if active >= max_connections {
+                    if !paused {
+                        warn!(
+                            "Reached max_connections ({}), pausing accept.",
+                            max_connections
+                        );
+                        paused = true;
+                    }
+                    thread::sleep(Duration::from_millis(100));
+                    continue;
+                } else if paused {
+                    if active > low_water_mark {
+                        thread::sleep(Duration::from_millis(100));
+                        continue;
+                    }
+                    info!(
+                        "Connections dropped to {} (at or below low-water mark {}), resuming accept.",
+                        active, low_water_mark
+                    );
+                    paused = false;
+                }
+
                 // Lock the listener to ensure exclusive access in this thread
                 let listener = listener.lock().unwrap();
 
@@ -81,23 +720,20 @@ impl Server {
                 match listener.accept() {
                     Ok((stream, addr)) => {
                         info!("New client connected: {}", addr);
-                        // Spawn a new thread to handle the connected client
-                        let is_running = Arc::clone(&is_running);
-                        thread::spawn(move || {
-                            let mut client = Client::new(stream);
-
-                            // Handle client communication while the server is running
-                            while is_running.load(Ordering::SeqCst) {
-                                if let Err(e) = client.handle() {
-                                    if e.kind() == ErrorKind::ConnectionReset {
-                                        info!("Client disconnected unexpectedly.");
-                                    } else {
-                                        error!("Error handling client: {}", e);
-                                    }
-                                    break;
-                                }
-                            }
-                        });
+                        // Count the connection as active as soon as it's accepted, not
+                        // once a worker dequeues it, so connections piling up in the
+                        // channel (waiting for a free worker) still count toward
+                        // `max_connections` instead of letting the acceptor keep
+                        // accepting unboundedly.
+                        active_connections.fetch_add(1, Ordering::SeqCst);
+                        // Hand the connection to the worker pool instead of spawning a thread.
+                        // `addr` is cloned up front since `send` moves its argument but the
+                        // error path below still needs it for logging.
+                        let log_addr = addr.clone();
+                        if sender.send((stream, addr)).is_err() {
+                            error!("Worker pool is gone, dropping connection: {}", log_addr);
+                            active_connections.fetch_sub(1, Ordering::SeqCst);
+                        }
                     }
                     Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
                         // No incoming connections, sleep briefly to reduce CPU usage
@@ -108,19 +744,355 @@ impl Server {
                     }
                 }
             }
+            // Dropping `sender` here unblocks every worker's `recv` so they can exit
         });
-        // Log that the server has stopped when the function exits
-        info!("Server stopped.");
+
+        *self.run_guard.lock().unwrap() = Some(RunGuard { acceptor, workers });
         Ok(())
     }
-    
-    /// Stops the server by setting the is_running flag to false
+
+    /// Stops the server: flips `is_running` so the non-blocking accept loop
+    /// and every worker notice on their next poll, then joins the acceptor
+    /// and worker threads. A worker sitting in a blocking read on a
+    /// non-broadcast client won't see the flag until its current read
+    /// unblocks, so the join is bounded by `read_timeout + SHUTDOWN_JOIN_MARGIN`
+    /// rather than a fixed constant, so a stuck thread can't hang shutdown
+    /// forever without `stop` spuriously timing out on every idle connection.
     pub fn stop(&self) {
-        if self.is_running.load(Ordering::SeqCst) {
-            self.is_running.store(false, Ordering::SeqCst);
-            info!("Shutdown signal sent.");
-        } else {
+        if !self.is_running.swap(false, Ordering::SeqCst) {
             warn!("Server was already stopped or not running.");
+            return;
+        }
+        info!("Shutdown signal sent.");
+
+        let guard = self.run_guard.lock().unwrap().take();
+        let Some(guard) = guard else {
+            return;
+        };
+
+        let (done_tx, done_rx) = mpsc::channel();
+        thread::spawn(move || {
+            guard.join();
+            let _ = done_tx.send(());
+        });
+
+        let join_timeout = self.read_timeout.saturating_add(SHUTDOWN_JOIN_MARGIN);
+        match done_rx.recv_timeout(join_timeout) {
+            Ok(()) => info!("Server stopped."),
+            Err(_) => warn!(
+                "Timed out after {:?} waiting for server threads to join.",
+                join_timeout
+            ),
         }
     }
-}
\ No newline at end of file
+
+    /// Blocks until the acceptor and every worker thread spawned by `run`
+    /// have exited, without itself flipping `is_running`. The acceptor only
+    /// exits once `stop` clears `is_running`, so this returns only after
+    /// `stop` has been called from another thread; that `stop` call then
+    /// finds no thread handles left and does not wait for the threads itself.
+    /// Returns immediately if `run` has not been called or the handles have
+    /// already been taken by `stop` or an earlier `join`.
+    pub fn join(&self) {
+        let guard = self.run_guard.lock().unwrap().take();
+        if let Some(guard) = guard {
+            guard.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Shutdown, TcpStream};
+
+    /// Starts a server on an ephemeral loopback port and gives the acceptor
+    /// thread a moment to start polling before returning.
+    fn start_server(workers: usize) -> (Server, SocketAddr) {
+        let server = Server::with_workers("127.0.0.1:0", workers).expect("bind");
+        let addr = server.local_addr().expect("local_addr");
+        server.run().expect("run");
+        thread::sleep(Duration::from_millis(50));
+        (server, addr)
+    }
+
+    fn send_framed(stream: &mut TcpStream, payload: &[u8]) -> io::Result<()> {
+        stream.write_all(&(payload.len() as u32).to_be_bytes())?;
+        stream.write_all(payload)?;
+        stream.flush()
+    }
+
+    fn read_framed(stream: &mut TcpStream) -> io::Result<Vec<u8>> {
+        let mut len_buf = [0u8; LENGTH_PREFIX_SIZE];
+        stream.read_exact(&mut len_buf)?;
+        let len = u32::from_be_bytes(len_buf) as usize;
+        let mut payload = vec![0u8; len];
+        stream.read_exact(&mut payload)?;
+        Ok(payload)
+    }
+
+    #[test]
+    fn disconnect_without_sending_frees_the_worker() {
+        let (server, addr) = start_server(1);
+
+        // Connect and disconnect immediately without sending any data. With a
+        // single-worker pool, a worker that failed to notice the clean
+        // disconnect would spin forever on the closed socket.
+        {
+            let _ = TcpStream::connect(addr).expect("connect");
+        }
+        thread::sleep(Duration::from_millis(100));
+
+        // The single worker must be free to serve this second, well-behaved client.
+        let mut stream = TcpStream::connect(addr).expect("connect");
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let message = EchoMessage {
+            content: "hello".to_string(),
+        };
+        send_framed(&mut stream, &message.encode_to_vec()).expect("send");
+        let reply = read_framed(&mut stream).expect("the worker should still be available");
+        let reply = EchoMessage::decode(reply.as_slice()).expect("decode");
+        assert_eq!(reply.content, "hello");
+
+        // Close the client cleanly so the worker notices right away instead of
+        // leaving `stop` to wait out the full read timeout.
+        stream.shutdown(Shutdown::Both).ok();
+        server.stop();
+    }
+
+    #[test]
+    fn oversized_frame_is_rejected() {
+        let mut server = Server::with_workers("127.0.0.1:0", 1).expect("bind");
+        server.set_max_frame_size(16);
+        let addr = server.local_addr().expect("local_addr");
+        server.run().expect("run");
+        thread::sleep(Duration::from_millis(50));
+
+        let mut stream = TcpStream::connect(addr).expect("connect");
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        // Declare a frame far larger than the configured max_frame_size.
+        stream
+            .write_all(&1024u32.to_be_bytes())
+            .expect("send header");
+        stream.flush().expect("flush");
+
+        // The server should close the connection instead of reading 1024 bytes.
+        let mut buf = [0u8; 1];
+        let n = stream.read(&mut buf).unwrap_or(0);
+        assert_eq!(
+            n, 0,
+            "server should close the connection on an oversized frame"
+        );
+
+        stream.shutdown(Shutdown::Both).ok();
+        server.stop();
+    }
+
+    #[test]
+    fn frames_larger_than_one_read_are_reassembled() {
+        let (server, addr) = start_server(1);
+        let mut stream = TcpStream::connect(addr).expect("connect");
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+
+        // A payload large enough that the server must issue several reads to
+        // reassemble the full frame.
+        let big = EchoMessage {
+            content: "x".repeat(64 * 1024),
+        };
+        send_framed(&mut stream, &big.encode_to_vec()).expect("send big frame");
+        let reply = read_framed(&mut stream).expect("read big frame");
+        let reply = EchoMessage::decode(reply.as_slice()).expect("decode");
+        assert_eq!(reply.content.len(), 64 * 1024);
+
+        stream.shutdown(Shutdown::Both).ok();
+        server.stop();
+    }
+
+    #[test]
+    fn multiple_frames_in_one_write_are_each_handled_in_turn() {
+        let (server, addr) = start_server(1);
+        let mut stream = TcpStream::connect(addr).expect("connect");
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+
+        let first = EchoMessage {
+            content: "first".to_string(),
+        };
+        let second = EchoMessage {
+            content: "second".to_string(),
+        };
+        let mut batched = Vec::new();
+        for message in [&first, &second] {
+            let payload = message.encode_to_vec();
+            batched.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+            batched.extend_from_slice(&payload);
+        }
+        stream.write_all(&batched).expect("send batched frames");
+        stream.flush().expect("flush");
+
+        let reply1 = EchoMessage::decode(read_framed(&mut stream).unwrap().as_slice()).unwrap();
+        let reply2 = EchoMessage::decode(read_framed(&mut stream).unwrap().as_slice()).unwrap();
+        assert_eq!(reply1.content, "first");
+        assert_eq!(reply2.content, "second");
+
+        stream.shutdown(Shutdown::Both).ok();
+        server.stop();
+    }
+
+    #[test]
+    fn max_connections_blocks_new_clients_until_capacity_frees_up() {
+        let mut server = Server::with_workers("127.0.0.1:0", 1).expect("bind");
+        server.set_max_connections(1);
+        let addr = server.local_addr().expect("local_addr");
+        server.run().expect("run");
+        thread::sleep(Duration::from_millis(50));
+
+        // Client A occupies the only connection slot and the only worker by
+        // staying connected without sending anything.
+        let client_a = TcpStream::connect(addr).expect("connect a");
+        thread::sleep(Duration::from_millis(100));
+
+        // Client B's TCP handshake can still complete (it just lands in the
+        // kernel's accept backlog), but the acceptor must not hand it to a
+        // worker while at capacity, so a message sent now goes unanswered.
+        let mut client_b = TcpStream::connect(addr).expect("connect b");
+        client_b
+            .set_read_timeout(Some(Duration::from_millis(300)))
+            .unwrap();
+        let message = EchoMessage {
+            content: "queued".to_string(),
+        };
+        send_framed(&mut client_b, &message.encode_to_vec()).expect("send from b");
+        let mut buf = [0u8; 1];
+        let err = client_b
+            .read(&mut buf)
+            .expect_err("b must not be served while at capacity");
+        assert!(matches!(
+            err.kind(),
+            ErrorKind::WouldBlock | ErrorKind::TimedOut
+        ));
+
+        // Freeing client A's slot should let the acceptor resume and finally
+        // hand client B's already-pending connection to a worker.
+        drop(client_a);
+        client_b
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let reply = read_framed(&mut client_b).expect("b should be served once capacity frees up");
+        let reply = EchoMessage::decode(reply.as_slice()).expect("decode");
+        assert_eq!(reply.content, "queued");
+
+        client_b.shutdown(Shutdown::Both).ok();
+        server.stop();
+    }
+
+    #[test]
+    fn broadcast_mode_fans_a_message_out_to_every_connected_peer() {
+        let mut server = Server::with_workers("127.0.0.1:0", 2).expect("bind");
+        server.set_broadcast_mode(true);
+        let addr = server.local_addr().expect("local_addr");
+        server.run().expect("run");
+        thread::sleep(Duration::from_millis(50));
+
+        let mut client_a = TcpStream::connect(addr).expect("connect a");
+        let mut client_b = TcpStream::connect(addr).expect("connect b");
+        for stream in [&client_a, &client_b] {
+            stream
+                .set_read_timeout(Some(Duration::from_secs(2)))
+                .unwrap();
+        }
+        // Give both workers a chance to register their peer before broadcasting.
+        thread::sleep(Duration::from_millis(100));
+
+        let message = EchoMessage {
+            content: "hi everyone".to_string(),
+        };
+        send_framed(&mut client_a, &message.encode_to_vec()).expect("send from a");
+
+        // Broadcast mode fans the message out to every registered peer,
+        // including the sender.
+        let reply_a = EchoMessage::decode(read_framed(&mut client_a).unwrap().as_slice()).unwrap();
+        let reply_b = EchoMessage::decode(read_framed(&mut client_b).unwrap().as_slice()).unwrap();
+        assert_eq!(reply_a.content, "hi everyone");
+        assert_eq!(reply_b.content, "hi everyone");
+
+        client_a.shutdown(Shutdown::Both).ok();
+        client_b.shutdown(Shutdown::Both).ok();
+        server.stop();
+    }
+
+    #[test]
+    fn idle_client_is_reaped_after_idle_timeout() {
+        let mut server = Server::with_workers("127.0.0.1:0", 1).expect("bind");
+        server.set_read_timeout(Duration::from_millis(50));
+        server.set_idle_timeout(Duration::from_millis(200));
+        let addr = server.local_addr().expect("local_addr");
+        server.run().expect("run");
+        thread::sleep(Duration::from_millis(50));
+
+        let mut stream = TcpStream::connect(addr).expect("connect");
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+
+        // Sit idle past `idle_timeout` without sending anything; the worker
+        // should give up on the client and close the connection rather than
+        // holding it (and itself) open forever.
+        let mut buf = [0u8; 1];
+        let n = stream.read(&mut buf).unwrap_or(0);
+        assert_eq!(
+            n, 0,
+            "idle client should be reaped and the connection closed"
+        );
+
+        server.stop();
+    }
+
+    #[test]
+    fn unix_socket_listener_echoes_messages() {
+        use std::os::unix::net::UnixStream;
+
+        let path = env::temp_dir().join(format!("ot_task_test_{}_unix_echo.sock", process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let server = Server::new_unix_with_workers(&path, 1).expect("bind unix socket");
+        server.run().expect("run");
+        thread::sleep(Duration::from_millis(50));
+
+        let mut stream = UnixStream::connect(&path).expect("connect");
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+
+        let message = EchoMessage {
+            content: "over unix".to_string(),
+        };
+        let payload = message.encode_to_vec();
+        stream
+            .write_all(&(payload.len() as u32).to_be_bytes())
+            .expect("send header");
+        stream.write_all(&payload).expect("send payload");
+        stream.flush().expect("flush");
+
+        let mut len_buf = [0u8; LENGTH_PREFIX_SIZE];
+        stream.read_exact(&mut len_buf).expect("read reply header");
+        let len = u32::from_be_bytes(len_buf) as usize;
+        let mut reply_buf = vec![0u8; len];
+        stream
+            .read_exact(&mut reply_buf)
+            .expect("read reply payload");
+        let reply = EchoMessage::decode(reply_buf.as_slice()).expect("decode");
+        assert_eq!(reply.content, "over unix");
+
+        stream.shutdown(Shutdown::Both).ok();
+        server.stop();
+        let _ = std::fs::remove_file(&path);
+    }
+}